@@ -1,8 +1,12 @@
 use core::time::Duration;
 
 pub mod cli;
+pub mod network;
+pub mod telemetry;
 pub mod utils;
 
+pub use network::bootstrap;
+
 /// Establish a connection to the server using the configured transport.
 ///
 /// Note: This should be run on a client machine to connect to a server.
@@ -4,7 +4,8 @@ use color_eyre::Report;
 async fn main() -> Result<(), Report> {
     coil::telemetry::attach_tracing_logger()
         .expect("received a malformed or invalid tracing directive");
-    coil::bootstrap().await.expect("bootstrap process failed");
+    let (_commands, handle) = coil::bootstrap().await.expect("bootstrap process failed");
+    handle.await.expect("controller task panicked")?;
 
     Ok(())
 }
@@ -1,5 +1,5 @@
 use std::{
-    collections::hash_map::DefaultHasher,
+    collections::{hash_map::DefaultHasher, HashMap},
     hash::{Hash, Hasher},
     ops::{Deref, DerefMut},
     time::Duration,
@@ -8,31 +8,256 @@ use std::{
 use color_eyre::{eyre::eyre, Report};
 use futures::prelude::stream::StreamExt;
 use libp2p::{
-    self,
-    core::{muxing::StreamMuxerBox, transport, upgrade},
+    self, autonat, connection_limits,
+    core::{muxing::StreamMuxerBox, transport, transport::OrTransport, upgrade},
+    dcutr,
     gossipsub::{
         Gossipsub, GossipsubConfigBuilder, GossipsubEvent, GossipsubMessage, IdentTopic,
         MessageAuthenticity, MessageId, ValidationMode,
     },
-    identity, mdns, mplex, noise,
+    identity,
+    kad::{self, record::store::MemoryStore},
+    mdns,
+    multiaddr::Protocol,
+    noise, quic, relay, request_response,
     swarm::{NetworkBehaviour, SwarmEvent},
-    tcp, Multiaddr, PeerId, Swarm, Transport,
+    tcp, websocket, yamux, Multiaddr, PeerId, Swarm, Transport,
+};
+use tokio::{
+    io::{self, AsyncBufReadExt},
+    sync::{mpsc, oneshot},
 };
-use tokio::io::{self, AsyncBufReadExt};
 
 use self::signals::spawn_signal_handler;
+use crate::cli::{Receiver, Transmitter};
 
+pub mod metrics;
+pub mod replication;
 pub mod signals;
 
+/// Commands accepted by a running [`Controller`].
+///
+/// These let `coil` be embedded as a library: a consumer drives the swarm
+/// from its own code by sending commands over the channel returned from
+/// [`bootstrap`], instead of being limited to typing lines into stdin.
+#[derive(Debug)]
+pub enum ControllerCommand {
+    /// Publish `data` to `topic`.
+    Publish { topic: IdentTopic, data: Vec<u8> },
+    /// Subscribe to a pubsub topic.
+    Subscribe(IdentTopic),
+    /// Dial the given peer.
+    Dial(Multiaddr),
+    /// Report the currently connected peers.
+    ListPeers(oneshot::Sender<Vec<PeerId>>),
+    /// Stop the controller's event loop.
+    Shutdown,
+    /// Replace the controller's connection limits.
+    SetConnectionLimits(ControllerConfig),
+    /// Reserve relay slots to enable NAT traversal, per
+    /// [`Controller::enable_nat_traversal`].
+    EnableNatTraversal(Vec<Multiaddr>),
+    /// Kick off a Kademlia bootstrap, per [`Controller::bootstrap_dht`].
+    BootstrapDht,
+    /// Locate the peers closest to a key in the DHT, per
+    /// [`Controller::get_closest_peers`].
+    GetClosestPeers(Vec<u8>),
+    /// Publish a record to the DHT, per [`Controller::put_record`].
+    PutRecord {
+        record: kad::Record,
+        quorum: kad::Quorum,
+    },
+    /// Look up a record previously stored with `PutRecord`, per
+    /// [`Controller::get_record`].
+    GetRecord(kad::RecordKey),
+    /// Disperse `data` under `topic` with the given replication factor and
+    /// quorum, per [`Controller::disperse`].
+    Disperse {
+        topic: IdentTopic,
+        data: Vec<u8>,
+        replication_factor: usize,
+        quorum: kad::Quorum,
+    },
+}
+
+/// Configuration for a [`Controller`], currently limited to connection
+/// accounting. Any field left as `None` is treated as unlimited.
+#[derive(Debug, Clone, Copy)]
+pub struct ControllerConfig {
+    pub max_established_incoming: Option<u32>,
+    pub max_established_outgoing: Option<u32>,
+    pub max_established_per_peer: Option<u32>,
+    pub max_pending_incoming: Option<u32>,
+    pub max_pending_outgoing: Option<u32>,
+}
+
+/// Unlimited defaults would defeat the purpose of this config, so
+/// [`ControllerConfig::default`] applies these deliberately conservative
+/// caps rather than `None`.
+const DEFAULT_MAX_ESTABLISHED_INCOMING: u32 = 64;
+const DEFAULT_MAX_ESTABLISHED_OUTGOING: u32 = 64;
+const DEFAULT_MAX_ESTABLISHED_PER_PEER: u32 = 8;
+const DEFAULT_MAX_PENDING_INCOMING: u32 = 128;
+const DEFAULT_MAX_PENDING_OUTGOING: u32 = 128;
+
+impl Default for ControllerConfig {
+    fn default() -> Self {
+        Self {
+            max_established_incoming: Some(DEFAULT_MAX_ESTABLISHED_INCOMING),
+            max_established_outgoing: Some(DEFAULT_MAX_ESTABLISHED_OUTGOING),
+            max_established_per_peer: Some(DEFAULT_MAX_ESTABLISHED_PER_PEER),
+            max_pending_incoming: Some(DEFAULT_MAX_PENDING_INCOMING),
+            max_pending_outgoing: Some(DEFAULT_MAX_PENDING_OUTGOING),
+        }
+    }
+}
+
+impl ControllerConfig {
+    /// Environment variables read by [`ControllerConfig::from_env`]. An
+    /// empty value means "unlimited"; anything else must parse as a `u32`.
+    pub const MAX_ESTABLISHED_INCOMING_ENV: &'static str = "COIL_MAX_ESTABLISHED_INCOMING";
+    pub const MAX_ESTABLISHED_OUTGOING_ENV: &'static str = "COIL_MAX_ESTABLISHED_OUTGOING";
+    pub const MAX_ESTABLISHED_PER_PEER_ENV: &'static str = "COIL_MAX_ESTABLISHED_PER_PEER";
+    pub const MAX_PENDING_INCOMING_ENV: &'static str = "COIL_MAX_PENDING_INCOMING";
+    pub const MAX_PENDING_OUTGOING_ENV: &'static str = "COIL_MAX_PENDING_OUTGOING";
+
+    /// Build a config from [`ControllerConfig::default`]'s conservative
+    /// caps, with each field overridden by its environment variable if set.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            max_established_incoming: read_limit_env(
+                Self::MAX_ESTABLISHED_INCOMING_ENV,
+                defaults.max_established_incoming,
+            ),
+            max_established_outgoing: read_limit_env(
+                Self::MAX_ESTABLISHED_OUTGOING_ENV,
+                defaults.max_established_outgoing,
+            ),
+            max_established_per_peer: read_limit_env(
+                Self::MAX_ESTABLISHED_PER_PEER_ENV,
+                defaults.max_established_per_peer,
+            ),
+            max_pending_incoming: read_limit_env(
+                Self::MAX_PENDING_INCOMING_ENV,
+                defaults.max_pending_incoming,
+            ),
+            max_pending_outgoing: read_limit_env(
+                Self::MAX_PENDING_OUTGOING_ENV,
+                defaults.max_pending_outgoing,
+            ),
+        }
+    }
+
+    fn connection_limits(&self) -> connection_limits::ConnectionLimits {
+        connection_limits::ConnectionLimits::default()
+            .with_max_established_incoming(self.max_established_incoming)
+            .with_max_established_outgoing(self.max_established_outgoing)
+            .with_max_established_per_peer(self.max_established_per_peer)
+            .with_max_pending_incoming(self.max_pending_incoming)
+            .with_max_pending_outgoing(self.max_pending_outgoing)
+    }
+}
+
+/// Read `env_var` as an optional connection limit: an empty value means
+/// unlimited (`None`); anything else must parse as a `u32`. An unset or
+/// unparsable value falls back to `default`, with the latter logged.
+fn read_limit_env(env_var: &str, default: Option<u32>) -> Option<u32> {
+    match std::env::var(env_var) {
+        Ok(value) => parse_limit_env(env_var, &value, default),
+        Err(_) => default,
+    }
+}
+
+/// Pure parsing logic behind [`read_limit_env`], split out so it can be unit
+/// tested without mutating process environment variables.
+fn parse_limit_env(env_var: &str, value: &str, default: Option<u32>) -> Option<u32> {
+    if value.trim().is_empty() {
+        return None;
+    }
+    match value.trim().parse::<u32>() {
+        Ok(limit) => Some(limit),
+        Err(err) => {
+            tracing::warn!("ignoring invalid {env_var} value {value:?}: {err:?}");
+            default
+        }
+    }
+}
+
+/// A base transport a [`TransportConfig`] can dial or listen over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BaseTransport {
+    /// Plain TCP.
+    Tcp,
+    /// QUIC over UDP. Provides its own TLS-based security and multiplexing,
+    /// so it bypasses the noise/muxer upgrade applied to the others.
+    Quic,
+    /// TCP wrapped in a WebSocket handshake.
+    WebSocket,
+}
+
+/// The stream multiplexer negotiated once noise has authenticated a
+/// connection. Yamux is the only option today; mplex is deprecated upstream
+/// and has been dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Muxer {
+    #[default]
+    Yamux,
+}
+
+/// Builder describing how a [`Controller`] reaches the network.
+///
+/// Multiple [`BaseTransport`]s are composed with [`OrTransport`] so a node
+/// can, for example, accept both TCP and QUIC dials at once.
+#[derive(Debug, Clone)]
+pub struct TransportConfig {
+    base: Vec<BaseTransport>,
+    muxer: Muxer,
+}
+
+impl Default for TransportConfig {
+    fn default() -> Self {
+        Self {
+            base: vec![BaseTransport::Tcp],
+            muxer: Muxer::default(),
+        }
+    }
+}
+
+impl TransportConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the base transport(s) to compose. Passing more than one wires
+    /// them together with [`OrTransport`].
+    pub fn with_base(mut self, base: Vec<BaseTransport>) -> Self {
+        self.base = base;
+        self
+    }
+
+    /// Set the stream muxer used atop noise-authenticated connections.
+    pub fn with_muxer(mut self, muxer: Muxer) -> Self {
+        self.muxer = muxer;
+        self
+    }
+}
+
 // FIXME: Refactor `Controller` to avoid circular depenencies or invalid/initialized state.
 
 /// NetworkBehaviour for multicast DNS using the Tokio runtime. Peers on the
 /// local network are automatically discovered and added to the topology.
 pub type MdnsBehaviour = mdns::Behaviour<mdns::tokio::Tokio>;
 
-/// Network behaviour that combines Gossipsub and mDNS.
+/// Network behaviour that combines Gossipsub, mDNS, NAT traversal, a
+/// Kademlia DHT, connection limits, and chunk dispersal.
 ///
 /// Floodsub is used for publish / subscribe and mDNS for local peer discovery.
+/// AutoNAT, the relay client, and DCUtR work together so that two peers
+/// sitting behind NATs can still reach each other: AutoNAT tells a node
+/// whether it is publicly reachable, the relay client gives it a fallback
+/// route through a public relay when it isn't, and DCUtR upgrades that
+/// relayed connection to a direct one via hole punching.
 ///
 /// The derive generates a delegating `NetworkBehaviour` implementation.
 #[derive(NetworkBehaviour)]
@@ -40,10 +265,21 @@ pub type MdnsBehaviour = mdns::Behaviour<mdns::tokio::Tokio>;
 pub struct ControllerBehaviour {
     gossipsub: Gossipsub,
     mdns: MdnsBehaviour,
+    autonat: autonat::Behaviour,
+    relay_client: relay::client::Behaviour,
+    dcutr: dcutr::Behaviour,
+    kademlia: kad::Behaviour<MemoryStore>,
+    connection_limits: connection_limits::Behaviour,
+    dispersal: replication::DispersalBehaviour,
 }
 
 impl ControllerBehaviour {
-    fn new(node: &Node, mdns: MdnsBehaviour) -> Result<ControllerBehaviour, Report> {
+    fn new(
+        node: &Node,
+        mdns: MdnsBehaviour,
+        relay_client: relay::client::Behaviour,
+        config: &ControllerConfig,
+    ) -> Result<ControllerBehaviour, Report> {
         // The content of each message is hashed, yielding the message ID.
         let message_id_fn = |message: &GossipsubMessage| {
             let mut hasher = DefaultHasher::new();
@@ -53,7 +289,7 @@ impl ControllerBehaviour {
 
         // Enable message signing. Use owner of key for author and random sequence number.
         let privacy = MessageAuthenticity::Signed(node.keypair().clone());
-        let config = GossipsubConfigBuilder::default()
+        let gossipsub_config = GossipsubConfigBuilder::default()
             .heartbeat_interval(Duration::from_millis(1053)) // Increase to aid with debugging by decreasing noise
             .validation_mode(ValidationMode::Strict) // Set message validation (default: Strict)
             .message_id_fn(message_id_fn) // content-address messages. No two messages of the same content will be propagated.
@@ -61,8 +297,24 @@ impl ControllerBehaviour {
             .map_err(|err| eyre!(err))?;
 
         // Build a gossipsub network behaviour from the privacy and config options.
-        let gossipsub = Gossipsub::new(privacy, config).map_err(|err| eyre!(err))?;
-        Ok(ControllerBehaviour { gossipsub, mdns })
+        let gossipsub = Gossipsub::new(privacy, gossipsub_config).map_err(|err| eyre!(err))?;
+
+        let autonat = autonat::Behaviour::new(node.peer_id(), autonat::Config::default());
+        let dcutr = dcutr::Behaviour::new(node.peer_id());
+        let kademlia = kad::Behaviour::new(node.peer_id(), MemoryStore::new(node.peer_id()));
+        let connection_limits = connection_limits::Behaviour::new(config.connection_limits());
+        let dispersal = replication::new_behaviour();
+
+        Ok(ControllerBehaviour {
+            gossipsub,
+            mdns,
+            autonat,
+            relay_client,
+            dcutr,
+            kademlia,
+            connection_limits,
+            dispersal,
+        })
     }
 }
 
@@ -71,6 +323,11 @@ impl ControllerBehaviour {
 pub enum ControllerEvent {
     Gossipsub(GossipsubEvent),
     Mdns(mdns::Event),
+    AutoNat(autonat::Event),
+    RelayClient(relay::client::Event),
+    Dcutr(dcutr::Event),
+    Kademlia(kad::Event),
+    Dispersal(request_response::Event<replication::DispersalRequest, replication::DispersalResponse>),
 }
 
 impl From<mdns::Event> for ControllerEvent {
@@ -85,12 +342,72 @@ impl From<GossipsubEvent> for ControllerEvent {
     }
 }
 
+impl From<autonat::Event> for ControllerEvent {
+    fn from(value: autonat::Event) -> Self {
+        Self::AutoNat(value)
+    }
+}
+
+impl From<relay::client::Event> for ControllerEvent {
+    fn from(value: relay::client::Event) -> Self {
+        Self::RelayClient(value)
+    }
+}
+
+impl From<dcutr::Event> for ControllerEvent {
+    fn from(value: dcutr::Event) -> Self {
+        Self::Dcutr(value)
+    }
+}
+
+impl From<kad::Event> for ControllerEvent {
+    fn from(value: kad::Event) -> Self {
+        Self::Kademlia(value)
+    }
+}
+
+impl From<request_response::Event<replication::DispersalRequest, replication::DispersalResponse>>
+    for ControllerEvent
+{
+    fn from(
+        value: request_response::Event<
+            replication::DispersalRequest,
+            replication::DispersalResponse,
+        >,
+    ) -> Self {
+        Self::Dispersal(value)
+    }
+}
+
+/// Maximum number of times [`Controller::disperse`] retries a single push
+/// to a single holder before giving up on that holder.
+const MAX_PUSH_RETRIES: u32 = 3;
+
+/// Upper bound on how long [`Controller::disperse`] waits for acks before
+/// giving up.
+const DISPERSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Resolve `quorum` into the number of acks required out of `holders`,
+/// mirroring how `kad::Quorum` is evaluated against a record's replica set.
+fn quorum_threshold(quorum: kad::Quorum, holders: usize) -> usize {
+    let holders = holders.max(1);
+    match quorum {
+        kad::Quorum::One => 1,
+        kad::Quorum::Majority => holders / 2 + 1,
+        kad::Quorum::All => holders,
+        kad::Quorum::N(n) => n.get(),
+    }
+    .min(holders)
+}
+
 /// Simple wrapper around a `Swarm` instance, with a pre-defined
 /// `NetworkBehaviour` implementation. At this time, a [Controller] is used
 ///  almost exactly the same as a `Swarm` instance.
 pub struct Controller {
     swarm: Swarm<ControllerBehaviour>,
     keypair: identity::Keypair,
+    chunk_store: replication::ChunkStore,
+    metrics: metrics::MetricsRecorder,
 }
 
 impl Controller {
@@ -105,9 +422,108 @@ impl Controller {
         Controller {
             swarm,
             keypair: node.keypair().clone(),
+            chunk_store: replication::ChunkStore::default(),
+            metrics: metrics::MetricsRecorder::new(),
         }
     }
 
+    /// Build the boxed transport consumed by [`Controller::new`], following
+    /// `config`'s choice of base transport(s) and muxer.
+    ///
+    /// The relay-client transport is always folded in alongside the chosen
+    /// base transport(s) so that `/p2p-circuit` addresses keep working;
+    /// QUIC is combined afterwards since it carries its own security and
+    /// multiplexing and so skips the noise/muxer upgrade the others share.
+    pub fn build_transport(
+        node: &Node,
+        relay_transport: relay::client::Transport,
+        config: &TransportConfig,
+    ) -> Result<transport::Boxed<(PeerId, StreamMuxerBox)>, Report> {
+        use futures::future::Either;
+
+        /// Noise-authenticate and multiplex a raw duplex-stream transport,
+        /// boxing the result down to the `(PeerId, StreamMuxerBox)` shape
+        /// every transport eventually needs to converge on.
+        fn upgrade_transport<T>(
+            transport: T,
+            node: &Node,
+            muxer: Muxer,
+        ) -> Result<transport::Boxed<(PeerId, StreamMuxerBox)>, Report>
+        where
+            T: Transport + Send + Unpin + 'static,
+            T::Output: futures::AsyncRead + futures::AsyncWrite + Unpin + Send + 'static,
+            T::Error: Send + Sync + 'static,
+            T::Dial: Send,
+            T::ListenerUpgrade: Send,
+        {
+            let noise_keys = noise::NoiseAuthenticated::xx(node.keypair())?;
+            let boxed = match muxer {
+                Muxer::Yamux => transport
+                    .upgrade(upgrade::Version::V1)
+                    .authenticate(noise_keys)
+                    .multiplex(yamux::YamuxConfig::default())
+                    .boxed(),
+            };
+            Ok(boxed)
+        }
+
+        /// Fold two already-converged `(PeerId, StreamMuxerBox)` transports
+        /// into one, collapsing the `Either` `OrTransport` leaves behind
+        /// since both sides have the same output shape.
+        fn fold<A, B>(transport: OrTransport<A, B>) -> transport::Boxed<(PeerId, StreamMuxerBox)>
+        where
+            A: Transport<Output = (PeerId, StreamMuxerBox)> + Send + Unpin + 'static,
+            A::Error: Send + Sync + 'static,
+            A::Dial: Send,
+            A::ListenerUpgrade: Send,
+            B: Transport<Output = (PeerId, StreamMuxerBox)> + Send + Unpin + 'static,
+            B::Error: Send + Sync + 'static,
+            B::Dial: Send,
+            B::ListenerUpgrade: Send,
+        {
+            transport
+                .map(|either_output, _| match either_output {
+                    Either::Left(output) => output,
+                    Either::Right(output) => output,
+                })
+                .boxed()
+        }
+
+        let mut transport = upgrade_transport(relay_transport, node, config.muxer)?;
+        let mut quic_requested = false;
+
+        for base in &config.base {
+            let next = match base {
+                BaseTransport::Tcp => upgrade_transport(
+                    tcp::tokio::Transport::new(tcp::Config::default().nodelay(true)),
+                    node,
+                    config.muxer,
+                )?,
+                BaseTransport::WebSocket => upgrade_transport(
+                    websocket::WsConfig::new(tcp::tokio::Transport::new(
+                        tcp::Config::default().nodelay(true),
+                    )),
+                    node,
+                    config.muxer,
+                )?,
+                BaseTransport::Quic => {
+                    quic_requested = true;
+                    continue;
+                }
+            };
+            transport = fold(OrTransport::new(transport, next));
+        }
+
+        if quic_requested {
+            let quic_transport = quic::tokio::Transport::new(quic::Config::new(node.keypair()))
+                .map(|(peer_id, connection), _| (peer_id, StreamMuxerBox::new(connection)))
+                .boxed();
+            transport = fold(OrTransport::new(transport, quic_transport));
+        }
+
+        Ok(transport)
+    }
+
     /// Returns a refernce to the controller's Swarm instance.
     pub fn swarm(&self) -> &Swarm<ControllerBehaviour> {
         &self.swarm
@@ -142,8 +558,231 @@ impl Controller {
         Ok(self.swarm.listen_on(addr).map(|_| {})?)
     }
 
+    /// Enable NAT traversal by reserving a slot on each of the given relays.
+    ///
+    /// A relay reservation lets a privately-addressed node be dialed through
+    /// `/p2p-circuit`. Once a connection comes in over that circuit, DCUtR
+    /// takes over and attempts to upgrade it to a direct connection via hole
+    /// punching.
+    pub fn enable_nat_traversal(&mut self, relay_addrs: Vec<Multiaddr>) -> Result<(), Report> {
+        for relay_addr in relay_addrs {
+            self.dial_peer(&relay_addr.to_string())?;
+            let circuit_addr = relay_addr.with(Protocol::P2pCircuit);
+            self.listen_on(circuit_addr)?;
+            tracing::info!("reserved relay slot via {relay_addr}");
+        }
+        Ok(())
+    }
+
+    /// Kick off a Kademlia bootstrap, refreshing the routing table against
+    /// the peers already known to it.
+    pub fn bootstrap_dht(&mut self) -> Result<(), Report> {
+        self.swarm
+            .behaviour_mut()
+            .kademlia
+            .bootstrap()
+            .map_err(|err| eyre!(err))?;
+        Ok(())
+    }
+
+    /// Locate the peers closest to `key` in the DHT's key-space.
+    pub fn get_closest_peers<K: Into<Vec<u8>>>(&mut self, key: K) -> kad::QueryId {
+        self.swarm.behaviour_mut().kademlia.get_closest_peers(key)
+    }
+
+    /// Publish `record` to the DHT, returning the ID of the resulting query.
+    pub fn put_record(
+        &mut self,
+        record: kad::Record,
+        quorum: kad::Quorum,
+    ) -> Result<kad::QueryId, Report> {
+        self.swarm
+            .behaviour_mut()
+            .kademlia
+            .put_record(record, quorum)
+            .map_err(|err| eyre!(err))
+    }
+
+    /// Look up a record previously stored with [`Controller::put_record`].
+    pub fn get_record(&mut self, key: kad::RecordKey) -> kad::QueryId {
+        self.swarm.behaviour_mut().kademlia.get_record(key)
+    }
+
+    /// Replace the controller's connection limits at runtime.
+    pub fn set_connection_limits(&mut self, config: &ControllerConfig) {
+        let limits = self.swarm.behaviour_mut().connection_limits.limits_mut();
+        *limits = config.connection_limits();
+    }
+
+    /// Disperse `data` under `topic` with the given replication factor,
+    /// returning once `quorum` of each chunk's assigned holders have acked.
+    ///
+    /// The payload is split into content-addressed chunks, each pushed
+    /// directly to `replication_factor` currently-connected peers selected
+    /// by rendezvous hashing. `quorum` is evaluated per chunk against the
+    /// number of holders actually selected for it, the same way
+    /// [`kad::Quorum`] is evaluated against a record's replica set in
+    /// [`Controller::put_record`]; any push whose stream fails is retried up
+    /// to [`MAX_PUSH_RETRIES`] times before giving up on that holder. If
+    /// giving up on enough holders makes a chunk's quorum unreachable, the
+    /// call fails fast instead of waiting out [`DISPERSE_TIMEOUT`], which
+    /// otherwise bounds the whole call.
+    ///
+    /// `replication_factor` must be greater than zero: a chunk with no
+    /// assigned holders could never be acked, which would hang the call
+    /// until the timeout for no reason.
+    ///
+    /// This drives the swarm itself via its own event loop, so nothing else
+    /// may poll the swarm while it runs. Calling it from a
+    /// [`ControllerCommand::Disperse`] handler inside [`Controller::run`] is
+    /// fine, since command handling and the swarm-event arm of `run`'s
+    /// `select!` never execute concurrently; just don't call it from a
+    /// separate task racing a running `run` loop.
+    pub async fn disperse(
+        &mut self,
+        topic: IdentTopic,
+        data: Vec<u8>,
+        replication_factor: usize,
+        quorum: kad::Quorum,
+    ) -> Result<(), Report> {
+        if replication_factor == 0 {
+            return Err(eyre!("cannot disperse to {topic}: replication_factor must be > 0"));
+        }
+
+        let peers: Vec<PeerId> = self.swarm.connected_peers().copied().collect();
+        if peers.is_empty() {
+            return Err(eyre!("cannot disperse to {topic}: no connected peers"));
+        }
+
+        let chunks = replication::split_into_chunks(&data);
+        // Acks still needed before a chunk's quorum is satisfied; a chunk is
+        // done once its entry is removed.
+        let mut needed: HashMap<replication::ChunkId, usize> = HashMap::new();
+        // Holders that have neither acked nor been given up on, used to detect an
+        // unreachable quorum as soon as it happens rather than timing out.
+        let mut potential: HashMap<replication::ChunkId, usize> = HashMap::new();
+        let mut in_flight: HashMap<request_response::RequestId, (replication::Chunk, PeerId)> =
+            HashMap::new();
+        let mut retries: HashMap<(replication::ChunkId, PeerId), u32> = HashMap::new();
+
+        for chunk in &chunks {
+            let holders = replication::select_holders(chunk.index, &peers, replication_factor);
+            if holders.is_empty() {
+                continue;
+            }
+            let chunk_id = (chunk.index, chunk.hash);
+            needed.insert(chunk_id, quorum_threshold(quorum, holders.len()));
+            potential.insert(chunk_id, holders.len());
+            for peer in &holders {
+                let request_id = self
+                    .swarm
+                    .behaviour_mut()
+                    .dispersal
+                    .send_request(peer, replication::DispersalRequest::Push(chunk.clone()));
+                in_flight.insert(request_id, (chunk.clone(), *peer));
+            }
+        }
+
+        if needed.is_empty() {
+            tracing::info!("dispersed 0 chunk(s) under topic {topic}: no chunk had any assigned holders");
+            return Ok(());
+        }
+
+        let deadline = tokio::time::sleep(DISPERSE_TIMEOUT);
+        tokio::pin!(deadline);
+
+        while !needed.is_empty() {
+            tokio::select! {
+                _ = &mut deadline => {
+                    return Err(eyre!(
+                        "disperse of {topic} timed out after {DISPERSE_TIMEOUT:?} with {} chunk(s) still short of quorum",
+                        needed.len()
+                    ));
+                }
+                event = self.select_next_some() => match event {
+                    SwarmEvent::Behaviour(ControllerEvent::Dispersal(request_response::Event::Message {
+                        message:
+                            request_response::Message::Response {
+                                request_id,
+                                response: replication::DispersalResponse::Ack(index, hash),
+                            },
+                        ..
+                    })) => {
+                        in_flight.remove(&request_id);
+                        let chunk_id = (index, hash);
+                        // An acked holder can no longer contribute a further
+                        // ack, so it leaves the pool of potential holders.
+                        if let Some(remaining_potential) = potential.get_mut(&chunk_id) {
+                            *remaining_potential = remaining_potential.saturating_sub(1);
+                        }
+                        if let Some(remaining) = needed.get_mut(&chunk_id) {
+                            *remaining -= 1;
+                            if *remaining == 0 {
+                                needed.remove(&chunk_id);
+                            }
+                        }
+                    }
+                    SwarmEvent::Behaviour(ControllerEvent::Dispersal(
+                        request_response::Event::OutboundFailure {
+                            peer,
+                            request_id,
+                            error,
+                            ..
+                        },
+                    )) => {
+                        if let Some((chunk, peer)) = in_flight.remove(&request_id) {
+                            let chunk_id = (chunk.index, chunk.hash);
+                            let attempts = retries.entry((chunk_id, peer)).or_insert(0);
+                            *attempts += 1;
+
+                            if *attempts > MAX_PUSH_RETRIES {
+                                tracing::warn!(
+                                    "dispersal push of chunk {} to {peer} failed {attempts} times; giving up on this holder: {error}",
+                                    chunk.index
+                                );
+                                if let Some(remaining_potential) = potential.get_mut(&chunk_id) {
+                                    *remaining_potential -= 1;
+                                    if let Some(&remaining_needed) = needed.get(&chunk_id) {
+                                        if *remaining_potential < remaining_needed {
+                                            return Err(eyre!(
+                                                "disperse of {topic} cannot reach quorum for chunk {}: too many holders failed",
+                                                chunk.index
+                                            ));
+                                        }
+                                    }
+                                }
+                            } else {
+                                tracing::warn!(
+                                    "dispersal push of chunk {} to {peer} failed: {error}; retrying ({attempts}/{MAX_PUSH_RETRIES})",
+                                    chunk.index
+                                );
+                                let retry_id = self
+                                    .swarm
+                                    .behaviour_mut()
+                                    .dispersal
+                                    .send_request(&peer, replication::DispersalRequest::Push(chunk.clone()));
+                                in_flight.insert(retry_id, (chunk, peer));
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        tracing::info!(
+            "dispersed {} chunk(s) under topic {topic} with replication factor {replication_factor}",
+            chunks.len()
+        );
+        Ok(())
+    }
+
     /// Start the main event loop, handling peers and swarm events.
-    pub async fn run(&mut self, topic: IdentTopic) -> Result<(), Report> {
+    pub async fn run(
+        &mut self,
+        topic: IdentTopic,
+        mut commands: Receiver<ControllerCommand>,
+    ) -> Result<(), Report> {
         let mut stdin = io::BufReader::new(io::stdin()).lines();
 
         spawn_signal_handler().await;
@@ -160,10 +799,93 @@ impl Controller {
                         return Err(eyre!("Stdin handle closed unexpectedly"))
                     }
                 }
-                event = self.select_next_some() => match event {
+                command = commands.recv() => match command {
+                    Some(ControllerCommand::Publish { topic, data }) => {
+                        match self.swarm.behaviour_mut().gossipsub.publish(topic, data) {
+                            Ok(message_id) => tracing::info!("Published message with ID {message_id}"),
+                            Err(err) => tracing::error!("Failed to publish message; error = {err:?}"),
+                        }
+                    }
+                    Some(ControllerCommand::Subscribe(topic)) => {
+                        match self.swarm.behaviour_mut().gossipsub.subscribe(&topic) {
+                            Ok(_true) => tracing::info!("Subscribed to new topic: {topic}"),
+                            Err(err) => tracing::error!("Subscription to topic {topic} failed: {err:?}"),
+                        }
+                    }
+                    Some(ControllerCommand::Dial(addr)) => {
+                        if let Err(err) = self.swarm.dial(addr.clone()) {
+                            tracing::error!("Failed to dial {addr}: {err:?}");
+                        }
+                    }
+                    Some(ControllerCommand::ListPeers(reply)) => {
+                        let peers = self.swarm.connected_peers().copied().collect();
+                        let _ = reply.send(peers);
+                    }
+                    Some(ControllerCommand::Shutdown) => {
+                        tracing::info!("Received shutdown command, stopping controller");
+                        return Ok(());
+                    }
+                    Some(ControllerCommand::SetConnectionLimits(config)) => {
+                        self.set_connection_limits(&config);
+                        tracing::info!("updated connection limits: {config:?}");
+                    }
+                    Some(ControllerCommand::EnableNatTraversal(relay_addrs)) => {
+                        if let Err(err) = self.enable_nat_traversal(relay_addrs) {
+                            tracing::error!("failed to enable NAT traversal: {err:?}");
+                        }
+                    }
+                    Some(ControllerCommand::BootstrapDht) => {
+                        if let Err(err) = self.bootstrap_dht() {
+                            tracing::error!("Kademlia bootstrap failed: {err:?}");
+                        }
+                    }
+                    Some(ControllerCommand::GetClosestPeers(key)) => {
+                        let query_id = self.get_closest_peers(key);
+                        tracing::debug!("started closest-peers query {query_id:?}");
+                    }
+                    Some(ControllerCommand::PutRecord { record, quorum }) => {
+                        match self.put_record(record, quorum) {
+                            Ok(query_id) => tracing::debug!("started put_record query {query_id:?}"),
+                            Err(err) => tracing::error!("put_record failed: {err:?}"),
+                        }
+                    }
+                    Some(ControllerCommand::GetRecord(key)) => {
+                        let query_id = self.get_record(key);
+                        tracing::debug!("started get_record query {query_id:?}");
+                    }
+                    Some(ControllerCommand::Disperse { topic, data, replication_factor, quorum }) => {
+                        if let Err(err) = self.disperse(topic, data, replication_factor, quorum).await {
+                            tracing::error!("disperse failed: {err:?}");
+                        }
+                    }
+                    None => return Err(eyre!("Command channel closed unexpectedly")),
+                }
+                event = self.select_next_some() => {
+                    self.metrics.record_swarm_event(&event);
+                    if let SwarmEvent::Behaviour(behaviour_event) = &event {
+                        self.metrics.record_behaviour_event(behaviour_event);
+                    }
+                    match event {
                     SwarmEvent::NewListenAddr { address, .. } => {
                         tracing::info!("Listening on {address:?}");
                     }
+                    SwarmEvent::IncomingConnectionError { send_back_addr, error, .. } => {
+                        tracing::warn!("rejected incoming connection from {send_back_addr}: {error}");
+                    }
+                    SwarmEvent::OutgoingConnectionError { peer_id, error, .. } => {
+                        tracing::warn!("outgoing connection to {peer_id:?} failed: {error}");
+                    }
+                    // Any peer we can gossip with is, by definition, a peer
+                    // we've established a connection to, so this is also
+                    // where every gossipsub-reachable peer gets seeded into
+                    // the Kademlia routing table (propagation_source on a
+                    // gossipsub message never carries an address of its
+                    // own to add directly).
+                    SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. } => {
+                        let address = endpoint.get_remote_address().clone();
+                        self.behaviour_mut().kademlia.add_address(&peer_id, address.clone());
+                        tracing::debug!("connection established with {peer_id} at {address}; seeded into Kademlia routing table");
+                    }
                     SwarmEvent::Behaviour(ControllerEvent::Gossipsub(GossipsubEvent::Message { propagation_source, message_id, message })) => {
                         let peer_id = propagation_source;
                         let message_data = String::from_utf8_lossy(&message.data);
@@ -176,7 +898,19 @@ impl Controller {
                                     .behaviour_mut()
                                     .gossipsub
                                     .add_explicit_peer(&peer);
+                                self
+                                    .behaviour_mut()
+                                    .kademlia
+                                    .add_address(&peer, multiaddr.clone());
                                 tracing::info!("mDNS discovered new peer: {peer} at {multiaddr}");
+
+                                let chunks: Vec<replication::Chunk> =
+                                    self.chunk_store.iter().cloned().collect();
+                                for chunk in chunks {
+                                    self.behaviour_mut()
+                                        .dispersal
+                                        .send_request(&peer, replication::DispersalRequest::Push(chunk));
+                                }
                             }
                         }
                         mdns::Event::Expired(list) => {
@@ -188,8 +922,102 @@ impl Controller {
                             }
                         }
                     }
+                    SwarmEvent::Behaviour(ControllerEvent::AutoNat(event)) => match event {
+                        autonat::Event::StatusChanged { old, new } => {
+                            tracing::info!("AutoNAT status changed from {old:?} to {new:?}");
+                        }
+                        autonat::Event::InboundProbe(result) => {
+                            tracing::debug!("AutoNAT inbound probe: {result:?}");
+                        }
+                        autonat::Event::OutboundProbe(result) => {
+                            tracing::debug!("AutoNAT outbound probe: {result:?}");
+                        }
+                    },
+                    SwarmEvent::Behaviour(ControllerEvent::RelayClient(event)) => {
+                        tracing::info!("relay client event: {event:?}");
+                    }
+                    SwarmEvent::Behaviour(ControllerEvent::Dcutr(event)) => match event.result {
+                        Ok(connection_id) => tracing::info!(
+                            "DCUtR hole punch to {} succeeded via {connection_id:?}",
+                            event.remote_peer_id
+                        ),
+                        Err(err) => tracing::warn!(
+                            "DCUtR hole punch to {} failed: {err}",
+                            event.remote_peer_id
+                        ),
+                    },
+                    SwarmEvent::Behaviour(ControllerEvent::Kademlia(event)) => match event {
+                        kad::Event::OutboundQueryProgressed { result, .. } => match result {
+                            kad::QueryResult::GetClosestPeers(Ok(ok)) => {
+                                for peer in &ok.peers {
+                                    self.behaviour_mut().gossipsub.add_explicit_peer(peer);
+                                }
+                                tracing::info!(
+                                    "Kademlia query found {} closest peers",
+                                    ok.peers.len()
+                                );
+                            }
+                            kad::QueryResult::GetClosestPeers(Err(err)) => {
+                                tracing::warn!("Kademlia closest-peers query failed: {err:?}");
+                            }
+                            kad::QueryResult::Bootstrap(result) => {
+                                tracing::info!("Kademlia bootstrap progressed: {result:?}");
+                            }
+                            kad::QueryResult::GetRecord(result) => {
+                                tracing::info!("Kademlia get_record result: {result:?}");
+                            }
+                            kad::QueryResult::PutRecord(result) => {
+                                tracing::info!("Kademlia put_record result: {result:?}");
+                            }
+                            _ => {}
+                        },
+                        kad::Event::RoutingUpdated { peer, .. } => {
+                            self.behaviour_mut().gossipsub.add_explicit_peer(&peer);
+                            tracing::debug!("Kademlia routing table updated with peer {peer}");
+                        }
+                        _ => {}
+                    },
+                    SwarmEvent::Behaviour(ControllerEvent::Dispersal(event)) => match event {
+                        request_response::Event::Message {
+                            peer,
+                            message: request_response::Message::Request {
+                                request: replication::DispersalRequest::Push(chunk),
+                                channel,
+                                ..
+                            },
+                        } => {
+                            let (index, hash) = (chunk.index, chunk.hash);
+                            self.chunk_store.insert(chunk);
+                            tracing::debug!("stored dispersed chunk {index}/{hash:x} pushed by {peer}");
+                            if self
+                                .behaviour_mut()
+                                .dispersal
+                                .send_response(channel, replication::DispersalResponse::Ack(index, hash))
+                                .is_err()
+                            {
+                                tracing::warn!("failed to ack dispersed chunk {index}/{hash:x} to {peer}: channel closed");
+                            }
+                        }
+                        request_response::Event::Message {
+                            message: request_response::Message::Response { .. },
+                            ..
+                        } => {
+                            // Acks for in-flight pushes are consumed directly by `disperse`'s
+                            // own event loop, not here.
+                        }
+                        request_response::Event::InboundFailure { peer, error, .. } => {
+                            tracing::warn!("dispersal request from {peer} failed: {error}");
+                        }
+                        request_response::Event::OutboundFailure { peer, error, .. } => {
+                            tracing::warn!("dispersal push to {peer} failed: {error}");
+                        }
+                        request_response::Event::ResponseSent { peer, .. } => {
+                            tracing::debug!("dispersal ack sent to {peer}");
+                        }
+                    },
                     _ => {}
                 }
+                }
             }
         }
     }
@@ -238,25 +1066,64 @@ impl Default for Node {
 /// Hard-coded string representing the topic to be used for pubsub.
 pub const PUBSUB_TOPIC: &str = "coil-05FjJDr9Y8z";
 
-/// Starts a [Swarm] to manage peers and events. The swarm listens by default,
-/// but will dial out to a peer if a multi-address is passed as a CLI argument.
+/// Environment variable holding a comma-separated list of Kademlia bootstrap
+/// peers, each a `Multiaddr` with a trailing `/p2p/<peer-id>` component.
+pub const KAD_BOOTSTRAP_PEERS_ENV: &str = "COIL_KAD_BOOTSTRAP_PEERS";
+
+/// Read [`KAD_BOOTSTRAP_PEERS_ENV`] and parse it into a list of bootstrap
+/// peer addresses, skipping and logging any entry that fails to parse.
+fn kad_bootstrap_peers() -> Vec<Multiaddr> {
+    let Ok(raw) = std::env::var(KAD_BOOTSTRAP_PEERS_ENV) else {
+        return Vec::new();
+    };
+    parse_bootstrap_peers(&raw)
+}
+
+/// Pure parsing logic behind [`kad_bootstrap_peers`], split out so it can be
+/// unit tested without mutating process environment variables.
+fn parse_bootstrap_peers(raw: &str) -> Vec<Multiaddr> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|addr| !addr.is_empty())
+        .filter_map(|addr| match addr.parse::<Multiaddr>() {
+            Ok(multiaddr) => Some(multiaddr),
+            Err(err) => {
+                tracing::warn!("ignoring invalid Kademlia bootstrap peer {addr}: {err:?}");
+                None
+            }
+        })
+        .collect()
+}
+
+/// Starts a [Swarm] to manage peers and events, spawning its event loop as a
+/// background task. The swarm listens by default, but will dial out to a
+/// peer if a multi-address is passed as a CLI argument.
+///
+/// Returns a [`Transmitter`] for sending [`ControllerCommand`]s to the
+/// running controller, alongside the [`tokio::task::JoinHandle`] for its
+/// event loop.
 ///
 /// [Swarm]: https://docs.rs/libp2p/latest/libp2p/struct.Swarm.html
-pub async fn bootstrap() -> Result<(), Report> {
+pub async fn bootstrap() -> Result<
+    (
+        Transmitter<ControllerCommand>,
+        tokio::task::JoinHandle<Result<(), Report>>,
+    ),
+    Report,
+> {
     let node = Node::init();
 
-    // TODO: Learn more about the transport setup process, then refactor if needed.
-    let transport_config = tcp::Config::default().nodelay(true);
-    let transport = tcp::tokio::Transport::new(transport_config)
-        .upgrade(upgrade::Version::V1)
-        .authenticate(noise::NoiseAuthenticated::xx(node.keypair())?)
-        .multiplex(mplex::MplexConfig::new())
-        .boxed();
+    // The relay-client transport has to be composed alongside the base
+    // transport so that `/p2p-circuit` addresses resolve to a stream routed
+    // through a relay, rather than failing to dial.
+    let (relay_transport, relay_client) = relay::client::new(node.peer_id());
+    let transport = Controller::build_transport(&node, relay_transport, &TransportConfig::default())?;
 
     let pubsub_topic = IdentTopic::new(PUBSUB_TOPIC);
 
     let mdns_behaviour = mdns::tokio::Behaviour::new(mdns::Config::default())?;
-    let behaviour = ControllerBehaviour::new(&node, mdns_behaviour)?;
+    let config = ControllerConfig::from_env();
+    let behaviour = ControllerBehaviour::new(&node, mdns_behaviour, relay_client, &config)?;
 
     let mut controller = Controller::new(transport, behaviour, node);
     match controller
@@ -274,7 +1141,98 @@ pub async fn bootstrap() -> Result<(), Report> {
         controller.dial_peer(to_dial)?;
     }
 
+    // Seed the Kademlia routing table with any configured bootstrap peers and
+    // kick off a bootstrap query so the DHT has more than the local network
+    // to work with.
+    let bootstrap_peers = kad_bootstrap_peers();
+    for addr in &bootstrap_peers {
+        match addr.iter().last() {
+            Some(Protocol::P2p(multihash)) => match PeerId::try_from(multihash) {
+                Ok(peer_id) => controller
+                    .behaviour_mut()
+                    .kademlia
+                    .add_address(&peer_id, addr.clone()),
+                Err(err) => tracing::warn!("bootstrap peer {addr} has an invalid peer ID: {err}"),
+            },
+            _ => tracing::warn!("bootstrap peer {addr} missing a /p2p/<peer-id> suffix"),
+        };
+    }
+    if !bootstrap_peers.is_empty() {
+        controller.bootstrap_dht()?;
+    }
+
     let listen_addr = "/ip4/0.0.0.0/tcp/15550".parse::<Multiaddr>()?;
     controller.listen_on(listen_addr)?;
-    controller.run(pubsub_topic.clone()).await
+
+    controller
+        .metrics
+        .spawn_server(metrics::default_metrics_addr())
+        .await?;
+
+    let (command_tx, command_rx) = mpsc::channel(64);
+    let handle = tokio::spawn(async move { controller.run(pubsub_topic, command_rx).await });
+
+    Ok((command_tx, handle))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn controller_config_defaults_are_bounded_not_unlimited() {
+        let config = ControllerConfig::default();
+        assert!(config.max_established_incoming.is_some());
+        assert!(config.max_established_outgoing.is_some());
+        assert!(config.max_established_per_peer.is_some());
+        assert!(config.max_pending_incoming.is_some());
+        assert!(config.max_pending_outgoing.is_some());
+    }
+
+    #[test]
+    fn parse_limit_env_empty_value_means_unlimited() {
+        assert_eq!(parse_limit_env("TEST_ENV", "", Some(10)), None);
+        assert_eq!(parse_limit_env("TEST_ENV", "   ", Some(10)), None);
+    }
+
+    #[test]
+    fn parse_limit_env_parses_valid_values() {
+        assert_eq!(parse_limit_env("TEST_ENV", "42", Some(10)), Some(42));
+    }
+
+    #[test]
+    fn parse_limit_env_falls_back_to_default_on_garbage() {
+        assert_eq!(parse_limit_env("TEST_ENV", "not-a-number", Some(10)), Some(10));
+    }
+
+    #[test]
+    fn parse_bootstrap_peers_skips_malformed_and_empty_entries() {
+        let raw = "/ip4/127.0.0.1/tcp/4001/p2p/12D3KooWGK5ipiNVm7vBaeB7dR6FQXoUzFXK3rDxdsWfHmBMV1rr, , not-a-multiaddr";
+        let peers = parse_bootstrap_peers(raw);
+        assert_eq!(peers.len(), 1);
+    }
+
+    #[test]
+    fn parse_bootstrap_peers_of_empty_string_is_empty() {
+        assert!(parse_bootstrap_peers("").is_empty());
+    }
+
+    #[test]
+    fn transport_config_defaults_to_tcp_and_yamux() {
+        let config = TransportConfig::default();
+        assert_eq!(config.base, vec![BaseTransport::Tcp]);
+        assert_eq!(config.muxer, Muxer::Yamux);
+    }
+
+    #[test]
+    fn transport_config_builder_overrides_base_and_muxer() {
+        let config = TransportConfig::new()
+            .with_base(vec![BaseTransport::Quic, BaseTransport::WebSocket])
+            .with_muxer(Muxer::Yamux);
+        assert_eq!(
+            config.base,
+            vec![BaseTransport::Quic, BaseTransport::WebSocket]
+        );
+        assert_eq!(config.muxer, Muxer::Yamux);
+    }
 }
@@ -0,0 +1,128 @@
+//! Prometheus metrics for swarm and pubsub observability.
+//!
+//! The only visibility into a running node used to be `tracing` log lines.
+//! [`MetricsRecorder`] wraps `libp2p`'s own [`Metrics`] type and a
+//! `prometheus_client` [`Registry`] so connection churn and behaviour
+//! activity recorded in [`Controller::run`](crate::network::Controller::run)
+//! can be scraped over HTTP in Prometheus text-exposition format.
+
+use std::{net::SocketAddr, sync::Arc};
+
+use color_eyre::Report;
+use libp2p::{metrics::Metrics, swarm::SwarmEvent};
+use prometheus_client::{encoding::text::encode, registry::Registry};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    sync::Mutex,
+};
+
+use crate::network::ControllerEvent;
+
+/// Default socket address the metrics HTTP endpoint listens on: one port
+/// above [`crate::cli::default_socket_addr`].
+pub fn default_metrics_addr() -> SocketAddr {
+    let mut addr = crate::cli::default_socket_addr();
+    addr.set_port(addr.port() + 1);
+    addr
+}
+
+/// Records swarm and behaviour activity into a `prometheus_client` registry
+/// and serves it over HTTP.
+pub struct MetricsRecorder {
+    registry: Arc<Mutex<Registry>>,
+    metrics: Metrics,
+}
+
+impl MetricsRecorder {
+    /// Build a recorder with its own freshly registered metrics.
+    pub fn new() -> Self {
+        let mut registry = Registry::default();
+        let metrics = Metrics::new(&mut registry);
+        Self {
+            registry: Arc::new(Mutex::new(registry)),
+            metrics,
+        }
+    }
+
+    /// Record transport-level activity (connection churn, listen addresses,
+    /// dial failures, ...) carried by every [`SwarmEvent`].
+    pub fn record_swarm_event<E>(&self, event: &SwarmEvent<E>) {
+        self.metrics.record(event);
+    }
+
+    /// Record behaviour-specific activity, for the behaviours `libp2p`
+    /// ships a `Recorder` implementation for.
+    pub fn record_behaviour_event(&self, event: &ControllerEvent) {
+        match event {
+            ControllerEvent::Gossipsub(event) => self.metrics.record(event),
+            ControllerEvent::Kademlia(event) => self.metrics.record(event),
+            ControllerEvent::RelayClient(event) => self.metrics.record(event),
+            ControllerEvent::Dcutr(event) => self.metrics.record(event),
+            // `libp2p::metrics` has no `Recorder` impl for AutoNAT, mDNS, or
+            // our own dispersal protocol yet.
+            ControllerEvent::AutoNat(_)
+            | ControllerEvent::Mdns(_)
+            | ControllerEvent::Dispersal(_) => {}
+        }
+    }
+
+    /// Spawn a background task serving the registry in Prometheus
+    /// text-exposition format at `http://<addr>/metrics`.
+    pub async fn spawn_server(&self, addr: SocketAddr) -> Result<(), Report> {
+        let listener = TcpListener::bind(addr).await?;
+        tracing::info!("metrics endpoint listening on http://{addr}/metrics");
+
+        let registry = Arc::clone(&self.registry);
+        tokio::spawn(async move {
+            loop {
+                let (stream, peer_addr) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        tracing::warn!("failed to accept metrics connection: {err:?}");
+                        continue;
+                    }
+                };
+                let registry = Arc::clone(&registry);
+                tokio::spawn(async move {
+                    if let Err(err) = serve_scrape(stream, &registry).await {
+                        tracing::warn!("metrics request from {peer_addr} failed: {err:?}");
+                    }
+                });
+            }
+        });
+
+        Ok(())
+    }
+}
+
+impl Default for MetricsRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Read (and discard) a single HTTP request and write back the registry's
+/// current encoding, regardless of the requested path.
+async fn serve_scrape(
+    mut stream: tokio::net::TcpStream,
+    registry: &Mutex<Registry>,
+) -> Result<(), Report> {
+    let mut buf = [0u8; 1024];
+    let _ = stream.read(&mut buf).await?;
+
+    let mut body = String::new();
+    encode(&mut body, &*registry.lock().await)?;
+
+    let response = format!(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: application/openmetrics-text; version=1.0.0; charset=utf-8\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n\
+         {body}",
+        body.len(),
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
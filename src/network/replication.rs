@@ -0,0 +1,286 @@
+//! Content replication over a dedicated request-response protocol.
+//!
+//! Gossipsub is best-effort: a message published while peers are still
+//! joining the mesh can simply be lost. This module gives a payload a
+//! concrete delivery guarantee instead. A publisher splits the payload into
+//! chunks, assigns each chunk to a deterministic subset of peers by
+//! rendezvous (highest random weight) hashing `(chunk_index, peer)`, and
+//! pushes each chunk directly over a [`request_response`] stream to every
+//! peer in that subset, waiting for a quorum of them to ack. Because every
+//! node computes the same assignment from the same inputs, receivers agree with
+//! senders on who is supposed to hold a given chunk without any further
+//! coordination, and newly discovered peers can be re-offered chunks that
+//! predate them.
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    io,
+};
+
+use async_trait::async_trait;
+use futures::prelude::*;
+use libp2p::{
+    request_response::{self, ProtocolSupport},
+    PeerId, StreamProtocol,
+};
+
+/// Protocol name negotiated for the dispersal request-response streams.
+pub const DISPERSAL_PROTOCOL: &str = "/coil/dispersal/1.0.0";
+
+/// Chunks are capped at 16 KiB so a single push fits comfortably in one
+/// request-response round trip.
+pub const CHUNK_SIZE: usize = 16 * 1024;
+
+/// Content address for a chunk: the hash of its bytes.
+pub type ChunkHash = u64;
+
+/// Hash `data`, yielding the [`ChunkHash`] chunks are keyed and acked by.
+pub fn hash_chunk(data: &[u8]) -> ChunkHash {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// One piece of a dispersed payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    pub hash: ChunkHash,
+    pub index: u32,
+    pub data: Vec<u8>,
+}
+
+/// Split `data` into content-addressed [`Chunk`]s of up to [`CHUNK_SIZE`]
+/// bytes each.
+pub fn split_into_chunks(data: &[u8]) -> Vec<Chunk> {
+    data.chunks(CHUNK_SIZE)
+        .enumerate()
+        .map(|(index, bytes)| Chunk {
+            hash: hash_chunk(bytes),
+            index: index as u32,
+            data: bytes.to_vec(),
+        })
+        .collect()
+}
+
+/// Deterministically select the `replication_factor` peers (out of
+/// `peers`) responsible for holding `chunk_index`, using rendezvous hashing
+/// so every node reaches the same assignment without coordination.
+pub fn select_holders(
+    chunk_index: u32,
+    peers: &[PeerId],
+    replication_factor: usize,
+) -> Vec<PeerId> {
+    let mut scored: Vec<(u64, PeerId)> = peers
+        .iter()
+        .map(|peer| (rendezvous_weight(chunk_index, peer), *peer))
+        .collect();
+    scored.sort_unstable_by(|(a_weight, a_peer), (b_weight, b_peer)| {
+        b_weight.cmp(a_weight).then_with(|| a_peer.cmp(b_peer))
+    });
+    scored.truncate(replication_factor);
+    scored.into_iter().map(|(_, peer)| peer).collect()
+}
+
+fn rendezvous_weight(chunk_index: u32, peer: &PeerId) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    chunk_index.hash(&mut hasher);
+    peer.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Identifies a chunk by its position in the payload *and* its content
+/// hash. `hash_chunk` alone isn't unique: two chunks with identical bytes
+/// (e.g. zero-padding, repeated blocks) collide, so every map keyed on a
+/// chunk must include `index` as well.
+pub type ChunkId = (u32, ChunkHash);
+
+/// Holds every chunk this node has been pushed, so it can re-offer them to
+/// newly discovered peers as the network changes.
+#[derive(Debug, Default)]
+pub struct ChunkStore {
+    chunks: HashMap<ChunkId, Chunk>,
+}
+
+impl ChunkStore {
+    /// Store `chunk`, overwriting any previous chunk with the same index and
+    /// hash.
+    pub fn insert(&mut self, chunk: Chunk) {
+        self.chunks.insert((chunk.index, chunk.hash), chunk);
+    }
+
+    /// Iterate over every chunk currently held.
+    pub fn iter(&self) -> impl Iterator<Item = &Chunk> {
+        self.chunks.values()
+    }
+}
+
+/// A request to push a chunk to its assigned holder.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DispersalRequest {
+    Push(Chunk),
+}
+
+/// Acknowledgement that a pushed chunk was stored, identified the same way
+/// it was requested: by index and content hash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DispersalResponse {
+    Ack(u32, ChunkHash),
+}
+
+/// Hand-rolled wire codec for [`DispersalRequest`]/[`DispersalResponse`]:
+/// big-endian length/hash prefixes followed by the raw chunk bytes, mirroring
+/// the lightweight framing the rest of `coil` already uses instead of
+/// pulling in a serialization crate for two small messages.
+#[derive(Debug, Clone, Default)]
+pub struct DispersalCodec;
+
+#[async_trait]
+impl request_response::Codec for DispersalCodec {
+    type Protocol = StreamProtocol;
+    type Request = DispersalRequest;
+    type Response = DispersalResponse;
+
+    async fn read_request<T>(&mut self, _: &Self::Protocol, io: &mut T) -> io::Result<Self::Request>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let index = read_u32(io).await?;
+        let hash = read_u64(io).await?;
+        let data = read_len_prefixed(io).await?;
+        Ok(DispersalRequest::Push(Chunk { hash, index, data }))
+    }
+
+    async fn read_response<T>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+    ) -> io::Result<Self::Response>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let index = read_u32(io).await?;
+        let hash = read_u64(io).await?;
+        Ok(DispersalResponse::Ack(index, hash))
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+        DispersalRequest::Push(chunk): Self::Request,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        io.write_all(&chunk.index.to_be_bytes()).await?;
+        io.write_all(&chunk.hash.to_be_bytes()).await?;
+        io.write_all(&(chunk.data.len() as u32).to_be_bytes()).await?;
+        io.write_all(&chunk.data).await?;
+        io.close().await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+        DispersalResponse::Ack(index, hash): Self::Response,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        io.write_all(&index.to_be_bytes()).await?;
+        io.write_all(&hash.to_be_bytes()).await?;
+        io.close().await
+    }
+}
+
+async fn read_u32<T: AsyncRead + Unpin>(io: &mut T) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    io.read_exact(&mut buf).await?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+async fn read_u64<T: AsyncRead + Unpin>(io: &mut T) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    io.read_exact(&mut buf).await?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+async fn read_len_prefixed<T: AsyncRead + Unpin>(io: &mut T) -> io::Result<Vec<u8>> {
+    let len = read_u32(io).await? as usize;
+    if len > CHUNK_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("dispersal chunk length {len} exceeds CHUNK_SIZE ({CHUNK_SIZE})"),
+        ));
+    }
+    let mut buf = vec![0u8; len];
+    io.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+/// The [`request_response::Behaviour`] instantiated with [`DispersalCodec`].
+pub type DispersalBehaviour = request_response::Behaviour<DispersalCodec>;
+
+/// Construct the dispersal behaviour, ready to be folded into
+/// `ControllerBehaviour`.
+pub fn new_behaviour() -> DispersalBehaviour {
+    request_response::Behaviour::new(
+        [(
+            StreamProtocol::new(DISPERSAL_PROTOCOL),
+            ProtocolSupport::Full,
+        )],
+        request_response::Config::default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_into_chunks_respects_chunk_size() {
+        let data = vec![7u8; CHUNK_SIZE * 2 + 1];
+        let chunks = split_into_chunks(&data);
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0].data.len(), CHUNK_SIZE);
+        assert_eq!(chunks[1].data.len(), CHUNK_SIZE);
+        assert_eq!(chunks[2].data.len(), 1);
+        assert_eq!(chunks[0].index, 0);
+        assert_eq!(chunks[2].index, 2);
+    }
+
+    #[test]
+    fn hash_chunk_is_deterministic_but_content_sensitive() {
+        assert_eq!(hash_chunk(b"same bytes"), hash_chunk(b"same bytes"));
+        assert_ne!(hash_chunk(b"these bytes"), hash_chunk(b"other bytes"));
+    }
+
+    #[test]
+    fn identical_chunks_at_different_indices_hash_the_same() {
+        // This is exactly the collision `ChunkId` exists to guard against:
+        // repeated/zero-padded blocks share a `ChunkHash`, so callers must
+        // key on `(index, hash)`, not `hash` alone.
+        let data = vec![0u8; CHUNK_SIZE * 2];
+        let chunks = split_into_chunks(&data);
+        assert_eq!(chunks[0].hash, chunks[1].hash);
+        assert_ne!(chunks[0].index, chunks[1].index);
+    }
+
+    #[test]
+    fn select_holders_is_deterministic_across_calls() {
+        let peers: Vec<PeerId> = (0..5).map(|_| PeerId::random()).collect();
+        let first = select_holders(0, &peers, 3);
+        let second = select_holders(0, &peers, 3);
+        assert_eq!(first, second);
+        assert_eq!(first.len(), 3);
+    }
+
+    #[test]
+    fn select_holders_caps_at_available_peers() {
+        let peers: Vec<PeerId> = (0..2).map(|_| PeerId::random()).collect();
+        let holders = select_holders(0, &peers, 5);
+        assert_eq!(holders.len(), 2);
+    }
+}